@@ -0,0 +1,163 @@
+//! Key-based (rather than text-based) editing of Cargo.toml documents.
+//!
+//! Reading and writing whole lines is fragile: it breaks as soon as a dependency spec is
+//! reformatted, split across lines, or reordered. This module instead parses the document with
+//! `toml_edit` and mutates the relevant table entries in place, so the rest of the file's
+//! formatting and comments survive untouched.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use toml_edit::{value, Document, InlineTable, Item, TableLike, Value};
+
+use crate::cmd::Flags;
+use crate::VersionSpec;
+
+/// Writes `doc` to `path`, unless `flags.dry_run` is set, in which case the change is logged
+/// instead of being applied to disk.
+fn write_document(
+    path: &Path,
+    doc: &Document,
+    flags: &Flags,
+    summary: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if flags.dry_run {
+        println!("+ (dry-run) would update {}:", path.display());
+        for line in summary {
+            println!("    {}", line);
+        }
+        return Ok(());
+    }
+
+    fs::write(path, doc.to_string())?;
+    Ok(())
+}
+
+/// Where a Cranelift crate's dependency spec may live.
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "build-dependencies"];
+
+/// Overwrites `dep[key]` with `val` in place when it's already set, so the rest of the inline
+/// table's formatting is left untouched; only inserts a fresh entry when the key is absent.
+fn set_table_field(dep: &mut dyn TableLike, key: &str, val: &str) {
+    if let Some(existing) = dep.get_mut(key) {
+        *existing = value(val);
+    } else {
+        dep.insert(key, value(val));
+    }
+}
+
+/// Points a dependency item at `version`, either by updating its `version`/`path` field in
+/// place (when it's already an inline or regular table) or by replacing it outright with a
+/// fresh inline table (when it's a bare string, e.g. `cranelift-wasm = "0.80.0"`).
+fn set_dependency_item(item: &mut Item, version: &VersionSpec, path_suffix: &str) {
+    match item.as_table_like_mut() {
+        Some(dep) => match version {
+            VersionSpec::Fixed(version_number) => {
+                dep.remove("path");
+                set_table_field(dep, "version", version_number);
+            }
+            VersionSpec::Path(path) => {
+                dep.remove("version");
+                set_table_field(dep, "path", &format!("{}{}", path, path_suffix));
+            }
+        },
+        None => {
+            let mut inline = InlineTable::new();
+            match version {
+                VersionSpec::Fixed(version_number) => {
+                    inline.insert("version", version_number.as_str().into());
+                }
+                VersionSpec::Path(path) => {
+                    inline.insert("path", format!("{}{}", path, path_suffix).into());
+                }
+            }
+            *item = Item::Value(Value::InlineTable(inline));
+        }
+    }
+}
+
+/// Finds `dep_name` in `[dependencies]` or `[build-dependencies]` (whichever actually has it),
+/// points it at `version`, and returns a one-line summary of the resulting entry for `--dry-run`
+/// logging.
+fn set_dependency_spec(
+    doc: &mut Document,
+    dep_name: &str,
+    version: &VersionSpec,
+    path_suffix: &str,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    for table_name in DEPENDENCY_TABLES {
+        let Some(table) = doc
+            .get_mut(table_name)
+            .and_then(|item| item.as_table_like_mut())
+        else {
+            continue;
+        };
+
+        if let Some(item) = table.get_mut(dep_name) {
+            set_dependency_item(item, version, path_suffix);
+            return Ok(format!("{} = {}", dep_name, item.to_string().trim()));
+        }
+    }
+
+    Err(format!(
+        "missing `{}` dependency in `[dependencies]` or `[build-dependencies]`",
+        dep_name
+    )
+    .into())
+}
+
+/// Rewrites the `cranelift-codegen` and `cranelift-wasm` entries of the Cranelift Cargo.toml at
+/// `path` to point at `version`, preserving the rest of the document. Honors `--dry-run`: the
+/// intended change is logged and the file is left untouched.
+pub(crate) fn set_cranelift_dependency_version(
+    path: &Path,
+    version: &VersionSpec,
+    flags: &Flags,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let content = fs::read_to_string(path)?;
+    let mut doc = content.parse::<Document>()?;
+
+    let codegen = set_dependency_spec(&mut doc, "cranelift-codegen", version, "codegen")?;
+    let wasm = set_dependency_spec(&mut doc, "cranelift-wasm", version, "wasm")?;
+
+    write_document(path, &doc, flags, &[codegen, wasm])
+}
+
+/// Rewrites the `rev = "..."` field of every `[patch.crates-io.cranelift-*]` entry in the
+/// top-level Cargo.toml at `path`, preserving the rest of the document. Honors `--dry-run`: the
+/// intended change is logged and the file is left untouched.
+pub(crate) fn set_cranelift_patch_rev(
+    path: &Path,
+    sha: &str,
+    flags: &Flags,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let content = fs::read_to_string(path)?;
+    let mut doc = content.parse::<Document>()?;
+
+    let patch = doc["patch"]["crates-io"]
+        .as_table_like_mut()
+        .ok_or("missing `[patch.crates-io]` table")?;
+
+    let cranelift_keys: Vec<String> = patch
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| key.starts_with("cranelift-"))
+        .collect();
+
+    if cranelift_keys.is_empty() {
+        return Err("no `[patch.crates-io.cranelift-*]` entries found".into());
+    }
+
+    let mut summary = Vec::with_capacity(cranelift_keys.len());
+    for key in cranelift_keys {
+        let entry = patch
+            .get_mut(&key)
+            .and_then(|item| item.as_table_like_mut())
+            .ok_or_else(|| format!("`{}` patch entry is not a table", key))?;
+        set_table_field(entry, "rev", sha);
+        summary.push(format!("[patch.crates-io.{}] rev = \"{}\"", key, sha));
+    }
+
+    write_document(path, &doc, flags, &summary)
+}
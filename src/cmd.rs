@@ -0,0 +1,128 @@
+//! A thin wrapper around `std::process::Command` shared by every external process this tool
+//! spawns (`git`/`hg`, `mach vendor rust`, `make`, `nproc`, the jit-test runner). It logs the
+//! exact command line before running (only under `--verbose` or `--dry-run`), captures or
+//! streams output uniformly, and reports
+//! failures through the crate's error type with the full invocation embedded.
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus, Output};
+
+use crate::error::{Context, Error};
+
+/// Global flags threaded through every spawned command.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Flags {
+    /// Log every command before running it.
+    pub(crate) verbose: bool,
+    /// Log the commands that mutate the target repository instead of running them.
+    pub(crate) dry_run: bool,
+}
+
+/// A command to be run, built up like `std::process::Command` but going through this module's
+/// logging and error conventions.
+pub(crate) struct Cmd {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Cmd {
+    pub(crate) fn new(program: impl Into<String>) -> Self {
+        Cmd {
+            program: program.into(),
+            args: Vec::new(),
+        }
+    }
+
+    pub(crate) fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    fn line(&self) -> String {
+        let mut line = self.program.clone();
+        for arg in &self.args {
+            line.push(' ');
+            line.push_str(arg);
+        }
+        line
+    }
+
+    fn log(&self, flags: &Flags) {
+        if flags.verbose || flags.dry_run {
+            println!("+ {}", self.line());
+        }
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command
+    }
+
+    /// Runs the command to completion and returns its captured stdout/stderr. Always actually
+    /// runs the command, `--dry-run` notwithstanding: meant for read-only commands like `git
+    /// diff` or `nproc`.
+    pub(crate) fn output(&self, flags: &Flags) -> Result<Output, Error> {
+        self.log(flags);
+        self.command()
+            .output()
+            .context(format!("couldn't start `{}`", self.line()))
+    }
+
+    /// Runs the command to completion with its stdout/stderr streamed to this process's own,
+    /// failing if it couldn't be spawned or exited non-zero. Always actually runs the command,
+    /// `--dry-run` notwithstanding: meant for read-only commands like the jit-test runner.
+    pub(crate) fn run(&self, flags: &Flags) -> Result<(), Error> {
+        self.log(flags);
+        let status = self
+            .command()
+            .spawn()
+            .context(format!("couldn't start `{}`", self.line()))?
+            .wait()
+            .context(format!("`{}` didn't run to completion", self.line()))?;
+        self.check_status(status)
+    }
+
+    /// Like [`Cmd::output`], but honors `--dry-run`: the command is logged but not actually run,
+    /// and a synthetic successful, empty output is returned instead. Meant for commands that
+    /// mutate the target repository, such as `git commit`.
+    pub(crate) fn output_unless_dry_run(&self, flags: &Flags) -> Result<Output, Error> {
+        self.log(flags);
+        if flags.dry_run {
+            return Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            });
+        }
+        self.command()
+            .output()
+            .context(format!("couldn't start `{}`", self.line()))
+    }
+
+    /// Like [`Cmd::run`], but honors `--dry-run`: the command is logged but not actually run.
+    /// Meant for commands that mutate the target repository, such as `mach vendor rust`.
+    pub(crate) fn run_unless_dry_run(&self, flags: &Flags) -> Result<(), Error> {
+        self.log(flags);
+        if flags.dry_run {
+            return Ok(());
+        }
+        let status = self
+            .command()
+            .spawn()
+            .context(format!("couldn't start `{}`", self.line()))?
+            .wait()
+            .context(format!("`{}` didn't run to completion", self.line()))?;
+        self.check_status(status)
+    }
+
+    fn check_status(&self, status: ExitStatus) -> Result<(), Error> {
+        if !status.success() {
+            return Err(Error::human(format!(
+                "`{}` exited with an error",
+                self.line()
+            )));
+        }
+        Ok(())
+    }
+}
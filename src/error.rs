@@ -0,0 +1,57 @@
+//! The crate's error type.
+//!
+//! Failures are split into two categories, following the old cargo `CargoError`/`Human` split:
+//! [`Error::Human`] is meant to be printed to the user as-is (a bad repo path, a dirty tree, a
+//! subprocess exiting non-zero), while [`Error::Internal`] covers anything unexpected and is
+//! rendered with its full context chain, since it likely points at a bug in this tool rather
+//! than something the user did.
+
+use thiserror::Error as ThisError;
+
+#[derive(Debug, ThisError)]
+pub(crate) enum Error {
+    #[error("{0}")]
+    Human(String),
+
+    #[error("{message}")]
+    Internal {
+        message: String,
+        #[source]
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+}
+
+impl Error {
+    /// Builds a [`Error::Human`] from a message meant to be shown to the user as-is.
+    pub(crate) fn human(msg: impl Into<String>) -> Self {
+        Error::Human(msg.into())
+    }
+
+    /// Whether this error should be printed to the user without a source chain or backtrace.
+    pub(crate) fn is_human(&self) -> bool {
+        matches!(self, Error::Human(_))
+    }
+}
+
+/// Attaches human-readable context to a fallible operation, turning its error into an
+/// [`Error::Internal`] that chains back to the original cause.
+///
+/// Don't call this on a `Result<_, Error>` that's already gone through classification (e.g. the
+/// return value of `VCS::commit`): since [`Error`] itself implements `std::error::Error`, doing
+/// so would always produce a fresh `Error::Internal` and silently demote an `Error::Human` into
+/// one. Just propagate an already-classified `Result<_, Error>` with `?` instead.
+pub(crate) trait Context<T> {
+    fn context(self, msg: impl Into<String>) -> Result<T, Error>;
+}
+
+impl<T, E> Context<T> for Result<T, E>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn context(self, msg: impl Into<String>) -> Result<T, Error> {
+        self.map_err(|source| Error::Internal {
+            message: msg.into(),
+            source: Some(Box::new(source)),
+        })
+    }
+}
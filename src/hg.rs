@@ -1,12 +1,15 @@
+use crate::cmd::{Cmd, Flags};
+use crate::error::Error;
 use crate::VCS;
 use std::path::PathBuf;
-use std::process::Command;
 
-pub(crate) struct HG;
+pub(crate) struct HG {
+    flags: Flags,
+}
 
 impl HG {
-    pub(crate) fn new() -> Self {
-        Self
+    pub(crate) fn new(flags: Flags) -> Self {
+        Self { flags }
     }
 }
 
@@ -17,13 +20,12 @@ impl VCS for HG {
         pathbuf.is_dir()
     }
 
-    fn commit(&self, msg: &str) -> Result<(), String> {
-        let output = Command::new("hg")
+    fn commit(&self, msg: &str) -> Result<(), Error> {
+        let output = Cmd::new("hg")
             .arg("commit")
             .arg("-m")
             .arg(msg)
-            .output()
-            .map_err(|err| format!("couldn't start hg commit: {}", err))?;
+            .output_unless_dry_run(&self.flags)?;
 
         if !output.status.success() {
             let stdout = String::from_utf8(output.stdout).unwrap_or("(stdout unavailable)".into());
@@ -31,21 +33,18 @@ impl VCS for HG {
             if stdout.trim() == "nothing changed" {
                 Ok(())
             } else {
-                Err(format!(
+                Err(Error::human(format!(
                     "hg commit returned an error: {} {}",
                     stdout, stderr
-                ))
+                )))
             }
         } else {
             Ok(())
         }
     }
 
-    fn has_diff(&self) -> Result<bool, String> {
-        let output = Command::new("hg")
-            .arg("diff")
-            .output()
-            .map_err(|err| format!("Could not start hg diff: {}", err))?;
+    fn has_diff(&self) -> Result<bool, Error> {
+        let output = Cmd::new("hg").arg("diff").output(&self.flags)?;
         Ok(!output.stdout.is_empty())
     }
 }
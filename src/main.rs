@@ -1,32 +1,37 @@
 use env::Args;
 use std::env;
-use std::fs::{canonicalize, File};
-use std::io::{Read, Write};
+use std::fs::canonicalize;
 use std::path::Path;
-use std::{
-    error::Error,
-    fmt,
-    process::{self, Command},
-};
+use std::process;
 
+mod cargo_edit;
+mod cmd;
+mod error;
 mod git;
 mod hg;
 
+use cmd::{Cmd, Flags};
+use error::{Context, Error};
+use semver::{Version, VersionReq};
+
 trait VCS {
     fn is_repo(&self, path: &str) -> bool;
-    fn commit(&self, msg: &str) -> Result<(), String>;
-    fn has_diff(&self) -> Result<bool, String>;
+    fn commit(&self, msg: &str) -> Result<(), Error>;
+    fn has_diff(&self) -> Result<bool, Error>;
 }
 
-fn get_vcs_for_repo(path: &str) -> Result<Box<dyn VCS>, Box<dyn Error>> {
-    let h = hg::HG::new();
-    let g = git::Git::new();
+fn get_vcs_for_repo(path: &str, flags: Flags) -> Result<Box<dyn VCS>, Error> {
+    let h = hg::HG::new(flags);
+    let g = git::Git::new(flags);
     if h.is_repo(path) {
         Ok(Box::new(h))
     } else if g.is_repo(path) {
         Ok(Box::new(g))
     } else {
-        Err(format!("Not a git or Mercurial repository: {}", path).into())
+        Err(Error::human(format!(
+            "Not a git or Mercurial repository: {}",
+            path
+        )))
     }
 }
 
@@ -34,41 +39,39 @@ const CRANELIFT_JS_SHELL_ARGS: &'static str =
     "--no-wasm-simd --shared-memory=off --wasm-compiler=cranelift";
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() {
     let mut args = env::args();
 
     let _ = args.next().unwrap();
 
-    let command = match args.next() {
-        Some(command) => command,
-        None => show_usage(),
+    let mut flags = Flags::default();
+    let command = loop {
+        match args.next() {
+            Some(arg) if arg == "--dry-run" => flags.dry_run = true,
+            Some(arg) if arg == "--verbose" => flags.verbose = true,
+            Some(command) => break command,
+            None => show_usage(),
+        }
     };
 
-    match command.as_str() {
-        "build" => run_build(args).await,
-        "bump" => run_bump(args).await,
-        "local" => run_local(args).await,
-        "test" => run_test(args).await,
+    let result = match command.as_str() {
+        "build" => run_build(args, flags).await,
+        "bump" => run_bump(args, flags).await,
+        "local" => run_local(args, flags).await,
+        "test" => run_test(args, flags).await,
         _ => show_usage(),
-    }
-}
-
-struct SimpleError(&'static str);
-
-impl fmt::Debug for SimpleError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
-    }
-}
+    };
 
-impl fmt::Display for SimpleError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+    if let Err(err) = result {
+        if err.is_human() {
+            eprintln!("error: {}", err);
+        } else {
+            eprintln!("internal error: {:?}", err);
+        }
+        process::exit(1);
     }
 }
 
-impl Error for SimpleError {}
-
 fn make_http_client() -> reqwest::Client {
     let mut headers = reqwest::header::HeaderMap::new();
 
@@ -87,25 +90,73 @@ fn make_http_client() -> reqwest::Client {
         .unwrap()
 }
 
-async fn get_cranelift_version(
+/// Fetches the published versions of `cranelift-codegen` and picks the highest one satisfying
+/// `requirement`, ignoring yanked releases. Prereleases are only considered when `requirement`
+/// explicitly asks for one; with no requirement at all, the highest stable release is picked.
+///
+/// The requested range is deliberately kept as a plain `Option<VersionReq>` argument rather than
+/// folded into `VersionSpec`: `VersionSpec` describes how to *write* a resolved dependency into
+/// a Cargo.toml (a concrete version or a local path), while this is an *input* constraint used
+/// only to pick that concrete version. Conflating the two would mean `replace_cranelift_version`
+/// has to handle a variant it can never legally receive.
+async fn resolve_cranelift_version(
     client: &reqwest::Client,
-) -> Result<String, Box<dyn std::error::Error>> {
+    requirement: Option<&VersionReq>,
+) -> Result<Version, Error> {
     const URL: &str = "https://crates.io/api/v1/crates/cranelift-codegen";
 
-    let resp = client.get(URL).send().await?.text().await?;
-
-    let object = json::parse(&resp)?;
-    let result = &object["crate"]["newest_version"];
-    Ok(result.to_string())
+    let resp = client
+        .get(URL)
+        .send()
+        .await
+        .context("couldn't reach crates.io")?
+        .text()
+        .await
+        .context("couldn't read crates.io response body")?;
+
+    let object = json::parse(&resp).context("couldn't parse crates.io response as JSON")?;
+
+    let allow_prerelease = requirement
+        .map(|req| req.comparators.iter().any(|comparator| !comparator.pre.is_empty()))
+        .unwrap_or(false);
+
+    let best = object["versions"]
+        .members()
+        .filter(|entry| !entry["yanked"].as_bool().unwrap_or(false))
+        .filter_map(|entry| entry["num"].as_str().and_then(|num| Version::parse(num).ok()))
+        .filter(|version| allow_prerelease || version.pre.is_empty())
+        .filter(|version| requirement.is_none_or(|req| req.matches(version)))
+        .max();
+
+    best.ok_or_else(|| {
+        Error::human(match requirement {
+            Some(req) => format!(
+                "no published, non-yanked Cranelift version satisfies `{}`",
+                req
+            ),
+            None => "no published, non-yanked stable Cranelift version was found".to_string(),
+        })
+    })
 }
 
+/// How to point a dependency at Cranelift: a resolved version, or a local checkout path.
+///
+/// Deliberately doesn't carry the requested range used to pick a [`VersionSpec::Fixed`] in the
+/// first place — see the doc comment on `resolve_cranelift_version` for why that's kept as a
+/// separate argument instead.
 enum VersionSpec {
     Fixed(String),
     Path(String),
 }
 
-/// Replace the cranelift version in the Cranelift Cargo.toml file.
-fn replace_cranelift_version(repo_path: &str, version: VersionSpec) {
+/// Replace the cranelift version in the Cranelift Cargo.toml file. Honors `flags.dry_run`: the
+/// file is left untouched and the intended change is printed instead, the same way `Cmd` already
+/// handles `--dry-run` for subprocess commands.
+fn replace_cranelift_version(
+    repo_path: &str,
+    version: VersionSpec,
+    flags: &Flags,
+) -> Result<(), Error> {
     println!("Replacing Cranelift version in its cargo file...");
     let cranelift_cargo_path = Path::new(&repo_path)
         .join("js")
@@ -114,127 +165,70 @@ fn replace_cranelift_version(repo_path: &str, version: VersionSpec) {
         .join("cranelift")
         .join("Cargo.toml");
 
-    let mut file = File::open(&cranelift_cargo_path).expect("couldn't open Cranelift cargo file");
-    let mut content = String::new();
-    file.read_to_string(&mut content)
-        .expect("couldn't read Cranelift cargo file content");
-
-    let content_lines = content.split("\n");
-
-    let new_content = content_lines
-        .map(|line| {
-            if line.starts_with("cranelift-codegen =") {
-                let replacement = match &version {
-                    VersionSpec::Fixed(version_number) => {
-                        format!("version = \"{}\"", version_number)
-                    }
-                    VersionSpec::Path(path) => format!("path = \"{}codegen\"", path),
-                };
-                format!(
-                    r#"cranelift-codegen = {{ {}, default-features = false }}"#,
-                    replacement
-                )
-            } else if line.starts_with("cranelift-wasm") {
-                let replacement = match &version {
-                    VersionSpec::Fixed(version_number) => {
-                        format!("version = \"{}\"", version_number)
-                    }
-                    VersionSpec::Path(path) => format!("path = \"{}wasm\"", path),
-                };
-                format!(r#"cranelift-wasm = {{ {} }}"#, replacement)
-            } else {
-                line.into()
-            }
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-
-    let mut file = File::create(&cranelift_cargo_path)
-        .expect("couldn't open Cranelift cargo file in write mode");
-    file.write_all(new_content.as_bytes())
-        .expect("couldn't write new Cranelift cargo content");
+    cargo_edit::set_cranelift_dependency_version(&cranelift_cargo_path, &version, flags)
+        .map_err(|source| Error::Internal {
+            message: "couldn't rewrite the Cranelift Cargo.toml".into(),
+            source: Some(source),
+        })?;
     println!("Done!");
+    Ok(())
 }
 
-/// Replace the cranelift version in the top-level Cargo.toml file.
-fn replace_commit_sha(repo_path: &str, sha: &str) {
+/// Replace the cranelift commit hash in the top-level Cargo.toml file. Honors `flags.dry_run`: the
+/// file is left untouched and the intended change is printed instead, the same way `Cmd` already
+/// handles `--dry-run` for subprocess commands.
+fn replace_commit_sha(repo_path: &str, sha: &str, flags: &Flags) -> Result<(), Error> {
     println!("Replacing Cranelift commit hash in the top-level cargo file...");
     let toplevel_cargo_path = Path::new(&repo_path).join("Cargo.toml");
 
-    let mut file = File::open(&toplevel_cargo_path).expect("couldn't open Cranelift cargo file");
-    let mut content = String::new();
-    file.read_to_string(&mut content)
-        .expect("couldn't read Cranelift cargo file content");
-
-    let content_lines = content.split("\n");
-
-    // Small state machine: when we see the patch line, we know we need to replace the line in 2
-    // lines. Very adhoc, but, oh well.
-    let mut replace_in = None;
-    let new_content = content_lines
-        .map(|line| {
-            replace_in = match replace_in {
-                Some(x) if x > 0 => Some(x - 1),
-                _ => None,
-            };
-            let ret = if let Some(0) = &replace_in {
-                format!(r#"rev = "{}""#, sha)
-            } else {
-                line.into()
-            };
-            if line.starts_with("[patch.crates-io.cranelift-") {
-                replace_in = Some(2);
-            }
-            ret
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-
-    let mut file = File::create(&toplevel_cargo_path)
-        .expect("couldn't open Cranelift cargo file in write mode");
-    file.write_all(new_content.as_bytes())
-        .expect("couldn't write new Cranelift cargo content");
+    cargo_edit::set_cranelift_patch_rev(&toplevel_cargo_path, sha, flags).map_err(|source| {
+        Error::Internal {
+            message: "couldn't rewrite the top-level Cargo.toml".into(),
+            source: Some(source),
+        }
+    })?;
     println!("Done!");
+    Ok(())
 }
 
-async fn find_last_commit_sha(
-    client: &reqwest::Client,
-) -> Result<String, Box<dyn std::error::Error>> {
+async fn find_last_commit_sha(client: &reqwest::Client) -> Result<String, Error> {
     const URL: &str = "https://api.github.com/repos/bytecodealliance/wasmtime/commits/HEAD";
 
-    let resp = client.get(URL).send().await?.text().await?;
-    let object = json::parse(&resp)?;
+    let resp = client
+        .get(URL)
+        .send()
+        .await
+        .context("couldn't reach api.github.com")?
+        .text()
+        .await
+        .context("couldn't read api.github.com response body")?;
+    let object = json::parse(&resp).context("couldn't parse api.github.com response as JSON")?;
     let result = &object["sha"];
 
     Ok(result.to_string())
 }
 
-fn mach_vendor_rust(allow_large: bool) -> Result<(), Box<dyn Error>> {
+fn mach_vendor_rust(allow_large: bool, flags: Flags) -> Result<(), Error> {
     println!("Running mach vendor rust...");
-    let mut command = Command::new("./mach");
-    command.arg("vendor").arg("rust");
+    let mut cmd = Cmd::new("./mach").arg("vendor").arg("rust");
     if allow_large {
-        command.arg("--build-peers-said-large-imports-were-ok");
+        cmd = cmd.arg("--build-peers-said-large-imports-were-ok");
     }
-    let status = command
-        .spawn()
-        .expect("couldn't run mach vendor rust")
-        .wait()?;
-    if !status.success() {
-        return Err(Box::new(SimpleError("Error when running mach vendor rust")));
-    }
-    Ok(())
+    cmd.run_unless_dry_run(&flags)
 }
 
-fn check_gecko_repo(repo_path: &str) -> Result<Box<dyn VCS>, Box<dyn Error>> {
+fn check_gecko_repo(repo_path: &str, flags: Flags) -> Result<Box<dyn VCS>, Error> {
     // Set cwd to the repository.
-    env::set_current_dir(repo_path)?;
+    env::set_current_dir(repo_path)
+        .context(format!("couldn't switch to repository {}", repo_path))?;
 
-    let repo = get_vcs_for_repo(repo_path)?;
+    let repo = get_vcs_for_repo(repo_path, flags)?;
 
     // Make sure the repository doesn't have any changes.
     if repo.has_diff()? {
-        return Err(Box::new(SimpleError("Diff isn't empty! aborting, please make sure the repository is clean before running this script".into())));
+        return Err(Error::human(
+            "Diff isn't empty! aborting, please make sure the repository is clean before running this script",
+        ));
     }
 
     Ok(repo)
@@ -266,35 +260,40 @@ fn get_repo_arg(args: &mut Args) -> String {
     }
 }
 
-async fn run_bump(mut args: Args) -> Result<(), Box<dyn Error>> {
+async fn run_bump(mut args: Args, flags: Flags) -> Result<(), Error> {
     let repo_path = &get_repo_arg(&mut args);
-    let repo = check_gecko_repo(repo_path)?;
+    let repo = check_gecko_repo(repo_path, flags)?;
 
-    let large_imports = if let Some(arg) = args.next() {
+    let mut large_imports = false;
+    let mut requirement: Option<VersionReq> = None;
+    for arg in args {
         match arg.as_str() {
-            "--allow-large" | "-a" => true,
-            _ => return Err(format!("unknown bump option: {}", arg).into()),
+            "--allow-large" | "-a" => large_imports = true,
+            _ if requirement.is_none() => {
+                requirement = Some(VersionReq::parse(&arg).map_err(|err| {
+                    Error::human(format!("invalid version requirement `{}`: {}", arg, err))
+                })?);
+            }
+            _ => return Err(Error::human(format!("unknown bump option: {}", arg))),
         }
-    } else {
-        false
-    };
+    }
 
     let http_client = make_http_client();
 
-    let version = get_cranelift_version(&http_client).await?;
+    let version = resolve_cranelift_version(&http_client, requirement.as_ref()).await?;
     println!("found version {}", version);
-    replace_cranelift_version(&repo_path, VersionSpec::Fixed(version));
+    replace_cranelift_version(&repo_path, VersionSpec::Fixed(version.to_string()), &flags)?;
 
     let last_commit = find_last_commit_sha(&http_client).await?;
     println!("last commit {}", last_commit);
-    replace_commit_sha(&repo_path, &last_commit);
+    replace_commit_sha(&repo_path, &last_commit, &flags)?;
 
     // Commit the change.
     println!("Committing bump patch...");
     repo.commit(&format!("Bug XXX - Bump Cranelift to {}; r?", last_commit))?;
 
     // Run mach vendor rust.
-    mach_vendor_rust(large_imports)?;
+    mach_vendor_rust(large_imports, flags)?;
 
     // Commit the vendor changges.
     println!("Committing vendor patch...");
@@ -304,69 +303,63 @@ async fn run_bump(mut args: Args) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-async fn run_build(mut args: Args) -> Result<(), Box<dyn Error>> {
+async fn run_build(mut args: Args, flags: Flags) -> Result<(), Error> {
     let build_dir = match args.next() {
         Some(build_dir) => build_dir,
-        None => {
-            return Err(Box::new(SimpleError(
-                "usage of `build`: build PATH_TO_BUILD_DIR",
-            )))
-        }
+        None => return Err(Error::human("usage of `build`: build PATH_TO_BUILD_DIR")),
     };
     let build_dir = canonicalize_dir(build_dir);
 
     // Switch to the build directory, run make, and tests.
-    env::set_current_dir(&build_dir).expect("couldn't set cwd to build dir");
+    env::set_current_dir(&build_dir).context("couldn't set cwd to build dir")?;
 
     // As many threads as there are cpus, or 8 by default.
-    let nproc = Command::new("nproc").output();
+    let nproc = Cmd::new("nproc").output(&flags);
     let nproc = match nproc {
         Ok(output) => {
-            let mut string = String::from_utf8(output.stdout)?;
+            let mut string =
+                String::from_utf8(output.stdout).context("nproc output wasn't valid UTF-8")?;
             string.retain(|c| !c.is_whitespace());
-            string.parse::<u32>()?
+            string
+                .parse::<u32>()
+                .context("couldn't parse nproc output as a number")?
         }
         Err(_) => 8,
     };
 
     println!("Running make...");
-    let status = Command::new("make")
+    Cmd::new("make")
         .arg(format!("-sj{}", nproc))
-        .spawn()
-        .expect("couldn't run make")
-        .wait()?;
-    if !status.success() {
-        return Err(Box::new(SimpleError("Error when running make")));
-    }
+        .run(&flags)?;
 
     Ok(())
 }
 
-async fn run_local(mut args: Args) -> Result<(), Box<dyn Error>> {
+async fn run_local(mut args: Args, flags: Flags) -> Result<(), Error> {
     // Read arguments: GECKO_PATH WASMTIME_PATH
     let repo_path = get_repo_arg(&mut args);
 
     let wasmtime_path = match args.next() {
         Some(path) => path,
         None => {
-            return Err(Box::new(SimpleError(
+            return Err(Error::human(
                 "usage of `local`: local GECKO_REPO_PATH WASMTIME_REPO_PATH",
-            )));
+            ));
         }
     };
     let cranelift_path = canonicalize_dir(wasmtime_path) + &"cranelift/";
 
-    let repo = check_gecko_repo(&repo_path)?;
+    let repo = check_gecko_repo(&repo_path, flags)?;
 
     // Replace the version of Cranelift in the Cargo.toml file.
-    replace_cranelift_version(&repo_path, VersionSpec::Path(cranelift_path));
+    replace_cranelift_version(&repo_path, VersionSpec::Path(cranelift_path), &flags)?;
 
     // Commit the change.
     println!("Committing bump patch...");
     repo.commit("No bug - do not check in - use local Cranelift")?;
 
     // Run mach vendor rust.
-    mach_vendor_rust(false)?;
+    mach_vendor_rust(false, flags)?;
 
     // Commit the vendor changges.
     println!("Committing vendor patch...");
@@ -377,16 +370,12 @@ async fn run_local(mut args: Args) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-async fn run_test(mut args: Args) -> Result<(), Box<dyn Error>> {
+async fn run_test(mut args: Args, flags: Flags) -> Result<(), Error> {
     let repo_path = get_repo_arg(&mut args);
 
     let build_path = canonicalize_dir(match args.next() {
         Some(path) => path,
-        None => {
-            return Err(Box::new(SimpleError(
-                "usage of `test`: test GECKO_DIR BUILD_DIR",
-            )))
-        }
+        None => return Err(Error::human("usage of `test`: test GECKO_DIR BUILD_DIR")),
     });
     let path_to_shell = build_path + "dist/bin/js";
 
@@ -401,26 +390,20 @@ async fn run_test(mut args: Args) -> Result<(), Box<dyn Error>> {
     };
 
     println!("Running tests...");
-    let status = Command::new(path_to_jit_tests)
+    Cmd::new(path_to_jit_tests.to_string_lossy().into_owned())
         .arg(path_to_shell)
         .arg(shell_args)
         .arg(which_tests)
-        .spawn()
-        .expect("couldn't run tests")
-        .wait()?;
+        .run(&flags)?;
 
-    if !status.success() {
-        Err(Box::new(SimpleError("Test failures!")))
-    } else {
-        Ok(())
-    }
+    Ok(())
 }
 
 fn show_usage() -> ! {
-    println!("usage: PROGRAM COMMAND");
+    println!("usage: PROGRAM [--dry-run] [--verbose] COMMAND");
     println!("  where COMMAND is one of:");
     println!(
-        "  bump GECKO_DIR                   bump to the latest available version of Cranelift in tree"
+        "  bump GECKO_DIR [VERSION_REQ]     bump Cranelift to the highest version in tree matching VERSION_REQ (default: the highest stable release)"
     );
     println!("  build BUILD_DIR                  run make in the build directory");
     println!(